@@ -1,6 +1,24 @@
 use std::env;
+use std::fmt;
 use std::process;
 
+/// Everything that can go wrong evaluating a token, reported with enough
+/// detail for `main` to point at the offending argument.
+#[derive(Debug)]
+enum CalcError {
+    StackUnderflow,
+    UnknownOperator(String),
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalcError::StackUnderflow => write!(f, "stack underflow"),
+            CalcError::UnknownOperator(op) => write!(f, "unknown operator '{}'", op),
+        }
+    }
+}
+
 struct ValueWithUnits {
     value: f64,
     numerator: String,
@@ -15,89 +33,145 @@ impl ValueWithUnits {
             denominator: denominator.to_owned(),
         }
     }
+}
+
+impl fmt::Display for ValueWithUnits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} / {}", self.value, self.numerator, self.denominator)
+    }
+}
+
+/// Parse a numeric literal, recognizing hex (`0x`), binary (`0b`), octal
+/// (`0o`), underscore digit separators, and scientific `e` notation
+/// before giving up.
+fn parse_number(token: &str) -> Option<f64> {
+    let cleaned: String = token.chars().filter(|&c| c != '_').collect();
+    if let Some(digits) = cleaned
+        .strip_prefix("0x")
+        .or_else(|| cleaned.strip_prefix("0X"))
+    {
+        return i64::from_str_radix(digits, 16).ok().map(|n| n as f64);
+    }
+    if let Some(digits) = cleaned
+        .strip_prefix("0b")
+        .or_else(|| cleaned.strip_prefix("0B"))
+    {
+        return i64::from_str_radix(digits, 2).ok().map(|n| n as f64);
+    }
+    if let Some(digits) = cleaned
+        .strip_prefix("0o")
+        .or_else(|| cleaned.strip_prefix("0O"))
+    {
+        return i64::from_str_radix(digits, 8).ok().map(|n| n as f64);
+    }
+    cleaned.parse::<f64>().ok()
+}
+
+/// Feed a single argument into the stack, returning the error (if any) so
+/// `main` can report it with the argument's position.
+fn process_arg(stack: &mut Vec<ValueWithUnits>, arg: &str) -> Result<(), CalcError> {
+    // Try to parse the argument as a number (decimal, hex, binary, octal,
+    // or scientific notation)
+    match parse_number(arg) {
+        // If it's a number, push it onto the stack
+        Some(num) => {
+            stack.push(ValueWithUnits::new(num, "", ""));
+            Ok(())
+        }
+        // If it's not a number, assume it's an operator
+        None => match arg {
+            "+" => {
+                let b = stack.pop().ok_or(CalcError::StackUnderflow)?;
+                let a = stack.pop().ok_or(CalcError::StackUnderflow)?;
+                stack.push(ValueWithUnits::new(
+                    a.value + b.value,
+                    a.numerator.as_str(),
+                    a.denominator.as_str(),
+                ));
+                Ok(())
+            }
+            "-" => {
+                let b = stack.pop().ok_or(CalcError::StackUnderflow)?;
+                let a = stack.pop().ok_or(CalcError::StackUnderflow)?;
+                stack.push(ValueWithUnits::new(
+                    a.value - b.value,
+                    a.numerator.as_str(),
+                    a.denominator.as_str(),
+                ));
+                Ok(())
+            }
+            "*" | "." => {
+                let b = stack.pop().ok_or(CalcError::StackUnderflow)?;
+                let a = stack.pop().ok_or(CalcError::StackUnderflow)?;
+                stack.push(ValueWithUnits::new(
+                    a.value * b.value,
+                    format!("{} {}", a.numerator, b.numerator).as_str(),
+                    format!("{} {}", a.denominator, b.denominator).as_str(),
+                ));
+                Ok(())
+            }
+            "/" => {
+                let b = stack.pop().ok_or(CalcError::StackUnderflow)?;
+                let a = stack.pop().ok_or(CalcError::StackUnderflow)?;
+                stack.push(ValueWithUnits::new(
+                    a.value / b.value,
+                    format!("{} {}", a.numerator, b.denominator).as_str(),
+                    format!("{} {}", a.denominator, b.numerator).as_str(),
+                ));
+                Ok(())
+            }
+            "%" => {
+                let b = stack.pop().ok_or(CalcError::StackUnderflow)?;
+                let a = stack.pop().ok_or(CalcError::StackUnderflow)?;
+                stack.push(ValueWithUnits::new(a.value % b.value, "", ""));
+                Ok(())
+            }
+            "mean" => {
+                let sum: f64 = stack.iter().map(|v| v.value).sum();
+                let count = stack.len() as f64;
+                let avg = sum / count;
+                stack.clear();
+                stack.push(ValueWithUnits::new(avg, "", ""));
+                Ok(())
+            }
+            _ => Err(CalcError::UnknownOperator(arg.to_owned())),
+        },
+    }
+}
 
-    fn to_string(&self) -> String {
-        format!("{} {} / {}", self.value, self.numerator, self.denominator)
+/// Print the failing argument list with a caret under the bad token, in
+/// the spirit of a compiler diagnostic.
+fn report_error(args: &[String], position: usize, err: &CalcError) {
+    eprintln!("args: {}", args.join(" "));
+    let mut caret_line = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            caret_line.push(' ');
+        }
+        if i == position {
+            caret_line.push_str(&"^".repeat(arg.len().max(1)));
+        } else {
+            caret_line.push_str(&" ".repeat(arg.len()));
+        }
     }
+    eprintln!("{} {} at position {}", caret_line, err, position);
 }
 
 fn main() {
     let mut stack: Vec<ValueWithUnits> = Vec::new();
 
+    let args: Vec<String> = env::args().skip(1).collect();
+
     // Loop through each argument provided
-    for arg in env::args().skip(1) {
-        // Try to parse the argument as a floating point number
-        match arg.parse::<f64>() {
-            // If it's a number, push it onto the stack
-            Ok(num) => {
-                stack.push(ValueWithUnits::new(num, "", ""));
-            }
-            // If it's not a number, assume it's an operator
-            Err(_) => {
-                match arg.as_str() {
-                    "+" => {
-                        let b = stack.pop().expect("Stack underflow");
-                        let a = stack.pop().expect("Stack underflow");
-                        stack.push(ValueWithUnits::new(
-                            a.value + b.value,
-                            a.numerator.as_str(),
-                            a.denominator.as_str(),
-                        ));
-                    }
-                    "-" => {
-                        let b = stack.pop().expect("Stack underflow");
-                        let a = stack.pop().expect("Stack underflow");
-                        stack.push(ValueWithUnits::new(
-                            a.value - b.value,
-                            a.numerator.as_str(),
-                            a.denominator.as_str(),
-                        ));
-                    }
-                    "*" | "." => {
-                        let b = stack.pop().expect("Stack underflow");
-                        let a = stack.pop().expect("Stack underflow");
-                        stack.push(ValueWithUnits::new(
-                            a.value * b.value,
-                            format!("{} {}", a.numerator, b.numerator).as_str(),
-                            format!("{} {}", a.denominator, b.denominator).as_str(),
-                        ));
-                    }
-                    "/" => {
-                        let b = stack.pop().expect("Stack underflow");
-                        let a = stack.pop().expect("Stack underflow");
-                        stack.push(ValueWithUnits::new(
-                            a.value / b.value,
-                            format!("{} {}", a.numerator, b.denominator).as_str(),
-                            format!("{} {}", a.denominator, b.numerator).as_str(),
-                        ));
-                    }
-                    "%" => {
-                        let b = stack.pop().expect("Stack underflow");
-                        let a = stack.pop().expect("Stack underflow");
-                        stack.push(ValueWithUnits::new(
-                            a.value % b.value,
-                            "",
-                            "",
-                        ));
-                    }
-                    "mean" => {
-                        let sum: f64 = stack.iter().map(|v| v.value).sum();
-                        let count = stack.len() as f64;
-                        let avg = sum / count;
-                        stack.clear();
-                        stack.push(ValueWithUnits::new(avg, "", ""));
-                    }
-                    _ => {
-                        println!("Unknown operator: {}", arg);
-                        process::exit(1);
-                    }
-                };
-            }
+    for (position, arg) in args.iter().enumerate() {
+        if let Err(err) = process_arg(&mut stack, arg) {
+            report_error(&args, position, &err);
+            process::exit(1);
         }
     }
 
     // Print each element of the stack on its own line
     for val in stack.iter().rev() {
-        println!("{}", val.to_string());
+        println!("{}", val);
     }
 }
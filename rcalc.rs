@@ -1,54 +1,193 @@
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::history::DefaultHistory;
+use rustyline::{Editor, Helper};
+use std::borrow::Cow::{self, Owned};
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::env;
+use std::fmt;
+use std::rc::Rc;
+
+/// Everything that can go wrong evaluating a token, reported with enough
+/// detail for `main` to point at the offending argument.
+#[derive(Debug)]
+enum CalcError {
+    StackUnderflow,
+    UnknownOperator(String),
+    DimensionMismatch { expected: String, found: String },
+    DivisionByZero,
+    ParseFailure(String),
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalcError::StackUnderflow => write!(f, "stack underflow"),
+            CalcError::UnknownOperator(op) => write!(f, "unknown operator '{}'", op),
+            CalcError::DimensionMismatch { expected, found } => {
+                write!(
+                    f,
+                    "dimension mismatch: expected {}, found {}",
+                    expected, found
+                )
+            }
+            CalcError::DivisionByZero => write!(f, "division by zero"),
+            CalcError::ParseFailure(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Number of base SI dimensions we track: length, mass, time, current,
+/// temperature, amount, luminosity.
+const NUM_DIMENSIONS: usize = 7;
+const DIMENSION_SYMBOLS: [&str; NUM_DIMENSIONS] = ["m", "kg", "s", "A", "K", "mol", "cd"];
+const LENGTH: usize = 0;
+
+/// A vector of signed exponents over the base SI dimensions, e.g. `m/s^2`
+/// is length=1, time=-2. Combining units means combining these vectors
+/// instead of concatenating unit strings.
+#[derive(Clone, Copy, PartialEq)]
+struct Dimension([i32; NUM_DIMENSIONS]);
+
+impl Dimension {
+    const DIMENSIONLESS: Dimension = Dimension([0; NUM_DIMENSIONS]);
+
+    fn base(index: usize, exponent: i32) -> Dimension {
+        let mut exponents = [0; NUM_DIMENSIONS];
+        exponents[index] = exponent;
+        Dimension(exponents)
+    }
+
+    fn combine(self, other: Dimension, sign: i32) -> Dimension {
+        let mut exponents = [0; NUM_DIMENSIONS];
+        for (exponent, (a, b)) in exponents.iter_mut().zip(self.0.iter().zip(other.0.iter())) {
+            *exponent = a + sign * b;
+        }
+        Dimension(exponents)
+    }
+
+    fn scale(self, power: i32) -> Dimension {
+        let mut exponents = [0; NUM_DIMENSIONS];
+        for (exponent, base) in exponents.iter_mut().zip(self.0.iter()) {
+            *exponent = base * power;
+        }
+        Dimension(exponents)
+    }
+
+    /// Halve every exponent, for `sqrt`. `None` if any exponent is odd.
+    fn halve(self) -> Option<Dimension> {
+        if self.0.iter().any(|exponent| exponent % 2 != 0) {
+            return None;
+        }
+        let mut exponents = [0; NUM_DIMENSIONS];
+        for (exponent, base) in exponents.iter_mut().zip(self.0.iter()) {
+            *exponent = base / 2;
+        }
+        Some(Dimension(exponents))
+    }
+
+    fn is_dimensionless(self) -> bool {
+        self.0.iter().all(|&exponent| exponent == 0)
+    }
+}
+
+impl fmt::Display for Dimension {
+    /// Render as a canonical `kg·m/s^2`-style string.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut numerator = Vec::new();
+        let mut denominator = Vec::new();
+        for (i, &exponent) in self.0.iter().enumerate() {
+            if exponent == 0 {
+                continue;
+            }
+            let symbol = DIMENSION_SYMBOLS[i];
+            if exponent > 0 {
+                numerator.push(if exponent == 1 {
+                    symbol.to_owned()
+                } else {
+                    format!("{}^{}", symbol, exponent)
+                });
+            } else {
+                denominator.push(if exponent == -1 {
+                    symbol.to_owned()
+                } else {
+                    format!("{}^{}", symbol, -exponent)
+                });
+            }
+        }
+        if numerator.is_empty() && denominator.is_empty() {
+            return Ok(());
+        }
+        let numerator = if numerator.is_empty() {
+            String::from("1")
+        } else {
+            numerator.join("\u{b7}")
+        };
+        if denominator.is_empty() {
+            write!(f, "{}", numerator)
+        } else {
+            write!(f, "{}/{}", numerator, denominator.join("\u{b7}"))
+        }
+    }
+}
 
 struct Unit {
     value: f64,
-    numerator: String,
-    denominator: String,
+    dimension: Dimension,
+    /// Multiplying `value` by `factor` gives the value in SI base units.
     factor: f64,
 }
 
 impl Unit {
-    fn new(value: f64, numerator: String, denominator: String, factor: f64) -> Self {
+    fn new(value: f64, dimension: Dimension, factor: f64) -> Self {
         Unit {
             value,
-            numerator,
-            denominator,
+            dimension,
             factor,
         }
     }
 
-    fn convert(&self, other: &Unit) -> (f64, String, String) {
-        let numerator = if self.numerator == other.numerator {
-            String::from(&other.numerator)
-        } else {
-            format!("{}{}", other.numerator, self.numerator)
-        };
-        let denominator = if self.denominator == other.denominator {
-            String::from(&other.denominator)
-        } else {
-            format!("{}{}", self.denominator, other.denominator)
-        };
-        let factor = other.factor * self.factor;
-        (self.value * factor / other.value, numerator, denominator)
+    /// Express this value on the scale of the unit with the given
+    /// `factor`. Callers are responsible for checking that the
+    /// dimensions actually match.
+    fn value_at_factor(&self, factor: f64) -> f64 {
+        self.value * self.factor / factor
+    }
+
+    /// Express this value on `target`'s scale. Callers are responsible for
+    /// checking that the dimensions actually match.
+    fn value_in(&self, target: &Unit) -> f64 {
+        self.value_at_factor(target.factor)
+    }
+}
+
+impl Clone for Unit {
+    fn clone(&self) -> Self {
+        Unit::new(self.value, self.dimension, self.factor)
     }
 }
 
 struct Calculator {
     stack: Vec<Unit>,
-    conversions: HashMap<String, f64>,
+    conversions: HashMap<String, (Dimension, f64)>,
+    registers: HashMap<String, Unit>,
 }
 
 impl Calculator {
-    fn new(conversions: HashMap<String, f64>) -> Self {
+    fn new(conversions: HashMap<String, (Dimension, f64)>) -> Self {
         Calculator {
             stack: Vec::new(),
             conversions,
+            registers: HashMap::new(),
         }
     }
 
-    fn push(&mut self, value: f64, numerator: String, denominator: String, factor: f64) {
-        let unit = Unit::new(value, numerator, denominator, factor);
+    fn push(&mut self, value: f64, dimension: Dimension, factor: f64) {
+        let unit = Unit::new(value, dimension, factor);
         self.stack.push(unit);
     }
 
@@ -56,96 +195,1029 @@ impl Calculator {
         self.stack.pop()
     }
 
-    fn add(&mut self) {
-        if let (Some(a), Some(b)) = (self.pop(), self.pop()) {
-            let (value, numerator, denominator) = b.convert(&a);
-            self.push(value, numerator, denominator, a.factor);
-        } else {
-            eprintln!("Error: not enough operands");
+    /// Validate both operands before popping either, so a failed check
+    /// (dimension mismatch, division by zero, ...) leaves the stack
+    /// exactly as it was instead of silently dropping the operands.
+    fn require_binary_operands(&self) -> Result<(&Unit, &Unit), CalcError> {
+        let len = self.stack.len();
+        if len < 2 {
+            return Err(CalcError::StackUnderflow);
         }
+        Ok((&self.stack[len - 1], &self.stack[len - 2]))
     }
 
-    fn sub(&mut self) {
-        if let (Some(a), Some(b)) = (self.pop(), self.pop()) {
-            let (value, numerator, denominator) = b.convert(&a);
-            self.push(value, numerator, denominator, a.factor);
-        } else {
-            eprintln!("Error: not enough operands");
+    fn add(&mut self) -> Result<(), CalcError> {
+        let (a, b) = self.require_binary_operands()?;
+        if a.dimension != b.dimension {
+            return Err(CalcError::DimensionMismatch {
+                expected: a.dimension.to_string(),
+                found: b.dimension.to_string(),
+            });
         }
+        let a = self.pop().unwrap();
+        let b = self.pop().unwrap();
+        let value = a.value + b.value_in(&a);
+        self.push(value, a.dimension, a.factor);
+        Ok(())
     }
 
-    fn mul(&mut self) {
-        if let (Some(a), Some(b)) = (self.pop(), self.pop()) {
-            let value = a.value * b.value;
-            let numerator = format!("{}{}", a.numerator, b.numerator);
-            let denominator = format!("{}{}", a.denominator, b.denominator);
-            let factor = a.factor * b.factor;
-            self.push(value, numerator, denominator, factor);
-        } else {
-            eprintln!("Error: not enough operands");
+    fn sub(&mut self) -> Result<(), CalcError> {
+        let (a, b) = self.require_binary_operands()?;
+        if a.dimension != b.dimension {
+            return Err(CalcError::DimensionMismatch {
+                expected: a.dimension.to_string(),
+                found: b.dimension.to_string(),
+            });
+        }
+        let a = self.pop().unwrap();
+        let b = self.pop().unwrap();
+        let value = b.value_in(&a) - a.value;
+        self.push(value, a.dimension, a.factor);
+        Ok(())
+    }
+
+    fn mul(&mut self) -> Result<(), CalcError> {
+        self.require_binary_operands()?;
+        let a = self.pop().unwrap();
+        let b = self.pop().unwrap();
+        let value = a.value * b.value;
+        let dimension = a.dimension.combine(b.dimension, 1);
+        let factor = a.factor * b.factor;
+        self.push(value, dimension, factor);
+        Ok(())
+    }
+
+    fn div(&mut self) -> Result<(), CalcError> {
+        let (a, _) = self.require_binary_operands()?;
+        if a.value == 0.0 {
+            return Err(CalcError::DivisionByZero);
         }
+        let a = self.pop().unwrap();
+        let b = self.pop().unwrap();
+        let value = b.value / a.value;
+        let dimension = b.dimension.combine(a.dimension, -1);
+        let factor = b.factor / a.factor;
+        self.push(value, dimension, factor);
+        Ok(())
     }
 
-    fn div(&mut self) {
-        if let (Some(a), Some(b)) = (self.pop(), self.pop()) {
-            let value = b.value / a.value;
-            let numerator = format!("{}{}", a.denominator, b.numerator);
-            let denominator = format!("{}{}", a.numerator, b.denominator);
-            let factor = b.factor / a.factor;
-            self.push(value, numerator, denominator, factor);
+    /// Raise the second-from-top value to the (dimensionless) top value.
+    /// A dimensioned base requires an integer exponent, which also scales
+    /// its dimension vector.
+    fn pow(&mut self) -> Result<(), CalcError> {
+        let (exponent, base) = self.require_binary_operands()?;
+        if !exponent.dimension.is_dimensionless() {
+            return Err(CalcError::DimensionMismatch {
+                expected: String::from(""),
+                found: exponent.dimension.to_string(),
+            });
+        }
+        if !base.dimension.is_dimensionless() && exponent.value.fract() != 0.0 {
+            return Err(CalcError::ParseFailure(String::from(
+                "exponent must be an integer when raising a dimensioned value",
+            )));
+        }
+        let exponent = self.pop().unwrap();
+        let base = self.pop().unwrap();
+        let power = exponent.value.round() as i32;
+        let dimension = base.dimension.scale(power);
+        let factor = base.factor.powi(power);
+        let value = base.value.powf(exponent.value);
+        self.push(value, dimension, factor);
+        Ok(())
+    }
+
+    fn sqrt(&mut self) -> Result<(), CalcError> {
+        let operand = self.pop().ok_or(CalcError::StackUnderflow)?;
+        if operand.value < 0.0 {
+            return Err(CalcError::ParseFailure(String::from(
+                "sqrt of a negative value",
+            )));
+        }
+        let dimension = operand.dimension.halve().ok_or_else(|| {
+            CalcError::ParseFailure(String::from(
+                "sqrt requires every dimension exponent to be even",
+            ))
+        })?;
+        self.push(operand.value.sqrt(), dimension, operand.factor.sqrt());
+        Ok(())
+    }
+
+    fn abs(&mut self) -> Result<(), CalcError> {
+        let operand = self.pop().ok_or(CalcError::StackUnderflow)?;
+        self.push(operand.value.abs(), operand.dimension, operand.factor);
+        Ok(())
+    }
+
+    fn neg(&mut self) -> Result<(), CalcError> {
+        let operand = self.pop().ok_or(CalcError::StackUnderflow)?;
+        self.push(-operand.value, operand.dimension, operand.factor);
+        Ok(())
+    }
+
+    /// Require `operand` to be dimensionless, the way every transcendental
+    /// function (`ln`, `exp`, `sin`, `cos`) needs.
+    fn require_dimensionless(operand: &Unit) -> Result<(), CalcError> {
+        if operand.dimension.is_dimensionless() {
+            Ok(())
         } else {
-            eprintln!("Error: not enough operands");
+            Err(CalcError::DimensionMismatch {
+                expected: String::from(""),
+                found: operand.dimension.to_string(),
+            })
+        }
+    }
+
+    fn ln(&mut self) -> Result<(), CalcError> {
+        let operand = self.pop().ok_or(CalcError::StackUnderflow)?;
+        Self::require_dimensionless(&operand)?;
+        self.push(operand.value.ln(), Dimension::DIMENSIONLESS, 1.0);
+        Ok(())
+    }
+
+    fn exp(&mut self) -> Result<(), CalcError> {
+        let operand = self.pop().ok_or(CalcError::StackUnderflow)?;
+        Self::require_dimensionless(&operand)?;
+        self.push(operand.value.exp(), Dimension::DIMENSIONLESS, 1.0);
+        Ok(())
+    }
+
+    fn sin(&mut self) -> Result<(), CalcError> {
+        let operand = self.pop().ok_or(CalcError::StackUnderflow)?;
+        Self::require_dimensionless(&operand)?;
+        self.push(operand.value.sin(), Dimension::DIMENSIONLESS, 1.0);
+        Ok(())
+    }
+
+    fn cos(&mut self) -> Result<(), CalcError> {
+        let operand = self.pop().ok_or(CalcError::StackUnderflow)?;
+        Self::require_dimensionless(&operand)?;
+        self.push(operand.value.cos(), Dimension::DIMENSIONLESS, 1.0);
+        Ok(())
+    }
+
+    fn dup(&mut self) -> Result<(), CalcError> {
+        let top = self.stack.last().ok_or(CalcError::StackUnderflow)?;
+        let copy = top.clone();
+        self.stack.push(copy);
+        Ok(())
+    }
+
+    fn drop_top(&mut self) -> Result<(), CalcError> {
+        self.pop().ok_or(CalcError::StackUnderflow)?;
+        Ok(())
+    }
+
+    fn swap(&mut self) -> Result<(), CalcError> {
+        let len = self.stack.len();
+        if len < 2 {
+            return Err(CalcError::StackUnderflow);
         }
+        self.stack.swap(len - 1, len - 2);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), CalcError> {
+        self.stack.clear();
+        Ok(())
+    }
+
+    fn store(&mut self, name: &str) -> Result<(), CalcError> {
+        let unit = self.pop().ok_or(CalcError::StackUnderflow)?;
+        self.registers.insert(name.to_owned(), unit);
+        Ok(())
     }
 
-    fn mean(&mut self) {
-        let sum = self.stack.iter().fold(0.0, |
+    fn recall(&mut self, name: &str) -> Result<(), CalcError> {
+        let unit = self
+            .registers
+            .get(name)
+            .ok_or_else(|| CalcError::ParseFailure(format!("unknown register '{}'", name)))?
+            .clone();
+        self.stack.push(unit);
+        Ok(())
+    }
 
+    /// Check that the stack height below the top value matches the
+    /// (rounded) top value, then pop it. Useful as a sanity check in
+    /// scripted input. Only pops once the assertion has passed, so a
+    /// failed assertion leaves the stack untouched.
+    fn assert_stack_height(&mut self) -> Result<(), CalcError> {
+        let top = self.stack.last().ok_or(CalcError::StackUnderflow)?;
+        let expected = top.value.round() as usize;
+        let remaining = self.stack.len() - 1;
+        if remaining != expected {
+            return Err(CalcError::ParseFailure(format!(
+                "expected stack height {}, found {}",
+                expected, remaining
+            )));
+        }
+        self.pop();
+        Ok(())
+    }
 
+    /// Check that every stack entry shares one dimension vector, the way
+    /// `sum`/`mean`/`min`/`max` all require before folding the stack.
+    fn require_uniform_dimension(&self) -> Result<Dimension, CalcError> {
+        let mut entries = self.stack.iter();
+        let first = entries.next().ok_or(CalcError::StackUnderflow)?;
+        for unit in entries {
+            if unit.dimension != first.dimension {
+                return Err(CalcError::DimensionMismatch {
+                    expected: first.dimension.to_string(),
+                    found: unit.dimension.to_string(),
+                });
+            }
+        }
+        Ok(first.dimension)
+    }
 
+    /// Fold every stack element into a running sum, requiring every
+    /// element share the same dimension, then replace the stack with the
+    /// single summed result.
+    fn sum(&mut self) -> Result<(), CalcError> {
+        let dimension = self.require_uniform_dimension()?;
+        let factor = self.stack[0].factor;
+        let total: f64 = self
+            .stack
+            .iter()
+            .map(|unit| unit.value_at_factor(factor))
+            .sum();
+        self.stack.clear();
+        self.push(total, dimension, factor);
+        Ok(())
+    }
 
+    /// `sum`, then divide the result by the element count it just
+    /// collapsed, turning the sum into an average.
+    fn mean(&mut self) -> Result<(), CalcError> {
+        let count = self.stack.len() as f64;
+        self.sum()?;
+        if let Some(total) = self.stack.last_mut() {
+            total.value /= count;
+        }
+        Ok(())
+    }
 
+    fn min(&mut self) -> Result<(), CalcError> {
+        let dimension = self.require_uniform_dimension()?;
+        let factor = self.stack[0].factor;
+        let minimum = self
+            .stack
+            .iter()
+            .map(|unit| unit.value_at_factor(factor))
+            .fold(f64::INFINITY, f64::min);
+        self.stack.clear();
+        self.push(minimum, dimension, factor);
+        Ok(())
+    }
 
+    fn max(&mut self) -> Result<(), CalcError> {
+        let dimension = self.require_uniform_dimension()?;
+        let factor = self.stack[0].factor;
+        let maximum = self
+            .stack
+            .iter()
+            .map(|unit| unit.value_at_factor(factor))
+            .fold(f64::NEG_INFINITY, f64::max);
+        self.stack.clear();
+        self.push(maximum, dimension, factor);
+        Ok(())
+    }
 
-fn main() {
+    /// Combine the whole stack multiplicatively, the way `mul` combines
+    /// two operands, but folded over every element at once.
+    fn product(&mut self) -> Result<(), CalcError> {
+        if self.stack.is_empty() {
+            return Err(CalcError::StackUnderflow);
+        }
+        let mut value = 1.0;
+        let mut dimension = Dimension::DIMENSIONLESS;
+        let mut factor = 1.0;
+        for unit in self.stack.iter() {
+            value *= unit.value;
+            dimension = dimension.combine(unit.dimension, 1);
+            factor *= unit.factor;
+        }
+        self.stack.clear();
+        self.push(value, dimension, factor);
+        Ok(())
+    }
+
+    fn count(&mut self) -> Result<(), CalcError> {
+        let count = self.stack.len() as f64;
+        self.stack.clear();
+        self.push(count, Dimension::DIMENSIONLESS, 1.0);
+        Ok(())
+    }
+}
+
+fn default_conversions() -> HashMap<String, (Dimension, f64)> {
     let mut conversions = HashMap::new();
-    conversions.insert(String::from("km"), 1000.0);
-    conversions.insert(String::from("m"), 1.0);
-    conversions.insert(String::from("cm"), 0.01);
-    conversions.insert(String::from("mm"), 0.001);
-    conversions.insert(String::from("mi"), 1609.344);
-    conversions.insert(String::from("yd"), 0.9144);
-    conversions.insert(String::from("ft"), 0.3048);
-    conversions.insert(String::from("in"), 0.0254);
+    let length = Dimension::base(LENGTH, 1);
+    conversions.insert(String::from("km"), (length, 1000.0));
+    conversions.insert(String::from("m"), (length, 1.0));
+    conversions.insert(String::from("cm"), (length, 0.01));
+    conversions.insert(String::from("mm"), (length, 0.001));
+    conversions.insert(String::from("mi"), (length, 1609.344));
+    conversions.insert(String::from("yd"), (length, 0.9144));
+    conversions.insert(String::from("ft"), (length, 0.3048));
+    conversions.insert(String::from("in"), (length, 0.0254));
+    conversions
+}
 
-    let args: Vec<String> = env::args().collect();
-    let mut calculator = Calculator::new(conversions);
-
-    for arg in args.iter().skip(1) {
-        match arg.as_str() {
-            "+" => calculator.add(),
-            "-" => calculator.sub(),
-            "*" | "." => calculator.mul(),
-            "/" => calculator.div(),
-            "mean" => calculator.mean(),
-            s => {
-                if let Ok(value) = s.parse::<f64>() {
-                    calculator.push(value, String::new(), String::new(), 1.0);
-                } else {
-                    let len = s.len();
-                    let (numerator, denominator) = s.split_at(len - 2);
-                    if let Some(&factor) = calculator.conversions.get(denominator) {
-                        calculator.push(1.0, String::from(numerator), String::from(denominator), factor);
-                    } else {
-                        eprintln!("Error: unknown operator {}", s);
-                        return;
+/// Parse a numeric literal, recognizing hex (`0x`), binary (`0b`), octal
+/// (`0o`), underscore digit separators, and scientific `e` notation
+/// before giving up.
+fn parse_number(token: &str) -> Option<f64> {
+    let cleaned: String = token.chars().filter(|&c| c != '_').collect();
+    if let Some(digits) = cleaned
+        .strip_prefix("0x")
+        .or_else(|| cleaned.strip_prefix("0X"))
+    {
+        return i64::from_str_radix(digits, 16).ok().map(|n| n as f64);
+    }
+    if let Some(digits) = cleaned
+        .strip_prefix("0b")
+        .or_else(|| cleaned.strip_prefix("0B"))
+    {
+        return i64::from_str_radix(digits, 2).ok().map(|n| n as f64);
+    }
+    if let Some(digits) = cleaned
+        .strip_prefix("0o")
+        .or_else(|| cleaned.strip_prefix("0O"))
+    {
+        return i64::from_str_radix(digits, 8).ok().map(|n| n as f64);
+    }
+    cleaned.parse::<f64>().ok()
+}
+
+/// Split a token like `10cm` into its magnitude (`10`) and unit suffix
+/// (`cm`) by peeling off the longest trailing run of alphabetic
+/// characters, instead of always assuming a two-character suffix.
+fn split_unit_suffix(token: &str) -> (&str, &str) {
+    let suffix_start = token
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| c.is_ascii_alphabetic())
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(token.len());
+    token.split_at(suffix_start)
+}
+
+/// Feed a single whitespace-delimited token into the calculator, the same
+/// way whether it came from `env::args()` or a REPL line. `sto`/`rcl` are
+/// handled one level up in `process_tokens` since they consume a second
+/// token for the register name.
+fn process_token(calculator: &mut Calculator, token: &str) -> Result<(), CalcError> {
+    match token {
+        "+" => calculator.add(),
+        "-" => calculator.sub(),
+        "*" | "." => calculator.mul(),
+        "/" => calculator.div(),
+        "sum" => calculator.sum(),
+        "mean" => calculator.mean(),
+        "min" => calculator.min(),
+        "max" => calculator.max(),
+        "product" => calculator.product(),
+        "count" => calculator.count(),
+        "dup" => calculator.dup(),
+        "drop" => calculator.drop_top(),
+        "swap" => calculator.swap(),
+        "clear" => calculator.clear(),
+        "!" => calculator.assert_stack_height(),
+        "^" => calculator.pow(),
+        "sqrt" => calculator.sqrt(),
+        "abs" => calculator.abs(),
+        "neg" => calculator.neg(),
+        "ln" => calculator.ln(),
+        "exp" => calculator.exp(),
+        "sin" => calculator.sin(),
+        "cos" => calculator.cos(),
+        s => {
+            if let Some(value) = parse_number(s) {
+                calculator.push(value, Dimension::DIMENSIONLESS, 1.0);
+                Ok(())
+            } else {
+                let (magnitude, suffix) = split_unit_suffix(s);
+                match (parse_number(magnitude), calculator.conversions.get(suffix)) {
+                    (Some(value), Some(&(dimension, factor))) => {
+                        calculator.push(value, dimension, factor);
+                        Ok(())
                     }
+                    _ => Err(CalcError::UnknownOperator(s.to_owned())),
                 }
             }
         }
     }
+}
+
+/// Run a full line of tokens through the calculator, handling the
+/// two-token `sto`/`rcl` register commands that `process_token` can't
+/// express on its own. Returns the index of the offending token on error.
+fn process_tokens(calculator: &mut Calculator, tokens: &[&str]) -> Result<(), (usize, CalcError)> {
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        let (outcome, consumed) = match token {
+            "sto" => match tokens.get(i + 1) {
+                Some(name) => (calculator.store(name), 2),
+                None => (
+                    Err(CalcError::ParseFailure(String::from(
+                        "sto requires a register name",
+                    ))),
+                    1,
+                ),
+            },
+            "rcl" => match tokens.get(i + 1) {
+                Some(name) => (calculator.recall(name), 2),
+                None => (
+                    Err(CalcError::ParseFailure(String::from(
+                        "rcl requires a register name",
+                    ))),
+                    1,
+                ),
+            },
+            _ => (process_token(calculator, token), 1),
+        };
+        outcome.map_err(|err| (i, err))?;
+        i += consumed;
+    }
+    Ok(())
+}
+
+/// Print the failing argument list with a caret under the bad token, in
+/// the spirit of a compiler diagnostic.
+fn report_error(tokens: &[&str], position: usize, err: &CalcError) {
+    eprintln!("args: {}", tokens.join(" "));
+    let mut caret_line = String::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 {
+            caret_line.push(' ');
+        }
+        if i == position {
+            caret_line.push_str(&"^".repeat(token.len().max(1)));
+        } else {
+            caret_line.push_str(&" ".repeat(token.len()));
+        }
+    }
+    eprintln!("{} {} at position {}", caret_line, err, position);
+}
 
+fn print_stack(calculator: &Calculator) {
     for unit in calculator.stack.iter() {
-        println!("{:.4} {}/{}", unit.value, unit.numerator, unit.denominator);
+        let dimension = unit.dimension.to_string();
+        let si_value = unit.value_at_factor(1.0);
+        if dimension.is_empty() {
+            println!("{:.4}", si_value);
+        } else {
+            println!("{:.4} {}", si_value, dimension);
+        }
+    }
+}
+
+/// True if `token` is a binary operator that consumes two stack entries,
+/// used by the highlighter to color operators.
+fn is_binary_op(token: &str) -> bool {
+    matches!(token, "+" | "-" | "*" | "." | "/" | "^")
+}
+
+/// Non-operator keywords the highlighter should still color as commands.
+fn is_keyword(token: &str) -> bool {
+    matches!(
+        token,
+        "sum"
+            | "mean"
+            | "min"
+            | "max"
+            | "product"
+            | "count"
+            | "dup"
+            | "drop"
+            | "swap"
+            | "clear"
+            | "sto"
+            | "rcl"
+            | "!"
+            | "sqrt"
+            | "abs"
+            | "neg"
+            | "ln"
+            | "exp"
+            | "sin"
+            | "cos"
+    )
+}
+
+/// Simulate `tokens` against a starting stack depth, mirroring
+/// `process_tokens`'s own dispatch, and report whether every operator in
+/// the line has enough operands to run. `depth` is the real `Calculator`
+/// stack height carried over from the last submitted line, so a lone
+/// trailing operator that already has its operands on the persistent
+/// stack is satisfied without needing a continuation line.
+fn tokens_satisfy_stack(tokens: &[&str], mut depth: usize) -> bool {
+    let mut i = 0;
+    while i < tokens.len() {
+        match tokens[i] {
+            "sto" => {
+                if depth < 1 {
+                    return false;
+                }
+                depth -= 1;
+                i += 2;
+            }
+            "rcl" => {
+                depth += 1;
+                i += 2;
+            }
+            "+" | "-" | "*" | "." | "/" | "^" => {
+                if depth < 2 {
+                    return false;
+                }
+                depth -= 1;
+                i += 1;
+            }
+            "sqrt" | "abs" | "neg" | "ln" | "exp" | "sin" | "cos" => {
+                if depth < 1 {
+                    return false;
+                }
+                i += 1;
+            }
+            "sum" | "mean" | "min" | "max" | "product" => {
+                if depth < 1 {
+                    return false;
+                }
+                depth = 1;
+                i += 1;
+            }
+            "count" => {
+                depth = 1;
+                i += 1;
+            }
+            "dup" => {
+                if depth < 1 {
+                    return false;
+                }
+                depth += 1;
+                i += 1;
+            }
+            "drop" | "!" => {
+                if depth < 1 {
+                    return false;
+                }
+                depth -= 1;
+                i += 1;
+            }
+            "swap" => {
+                if depth < 2 {
+                    return false;
+                }
+                i += 1;
+            }
+            "clear" => {
+                depth = 0;
+                i += 1;
+            }
+            _ => {
+                depth += 1;
+                i += 1;
+            }
+        }
+    }
+    true
+}
+
+/// Shares the real `Calculator`'s stack height with the `Validator` impl
+/// below, updated by `run_repl` after every submitted line.
+struct CalcHelper {
+    stack_depth: Rc<Cell<usize>>,
+}
+
+impl Completer for CalcHelper {
+    type Candidate = String;
+}
+
+impl Hinter for CalcHelper {
+    type Hint = String;
+}
+
+impl Helper for CalcHelper {}
+
+impl Highlighter for CalcHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let mut highlighted = String::with_capacity(line.len());
+        for (i, token) in line.split_whitespace().enumerate() {
+            if i > 0 {
+                highlighted.push(' ');
+            }
+            if parse_number(token).is_some() {
+                highlighted.push_str(&format!("\x1b[36m{}\x1b[0m", token)); // numbers: cyan
+            } else if is_binary_op(token) || is_keyword(token) {
+                highlighted.push_str(&format!("\x1b[33m{}\x1b[0m", token)); // operators: yellow
+            } else {
+                highlighted.push_str(&format!("\x1b[32m{}\x1b[0m", token)); // unit suffixes: green
+            }
+        }
+        Owned(highlighted)
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        true
+    }
+}
+
+impl Validator for CalcHelper {
+    /// Ask for a continuation line only when the pending input can't run
+    /// without more operands than are available, counting both the
+    /// tokens on this line and whatever the persistent stack already
+    /// holds. A line that ends in an operator whose operands were
+    /// already pushed on a previous line (the usual RPN REPL workflow)
+    /// is satisfied by `stack_depth` alone and submits immediately.
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let tokens: Vec<&str> = ctx.input().split_whitespace().collect();
+        if tokens_satisfy_stack(&tokens, self.stack_depth.get()) {
+            Ok(ValidationResult::Valid(None))
+        } else {
+            Ok(ValidationResult::Incomplete)
+        }
+    }
+}
+
+fn history_path() -> std::path::PathBuf {
+    let mut path = dirs_next_home();
+    path.push(".rcalc_history");
+    path
+}
+
+/// Minimal stand-in for a `dirs`-style home lookup so we don't pull in
+/// another dependency just for one path.
+fn dirs_next_home() -> std::path::PathBuf {
+    env::var_os("HOME")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+}
+
+fn run_repl() -> rustyline::Result<()> {
+    let mut calculator = Calculator::new(default_conversions());
+    let stack_depth = Rc::new(Cell::new(calculator.stack.len()));
+    let mut editor: Editor<CalcHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(CalcHelper {
+        stack_depth: Rc::clone(&stack_depth),
+    }));
+
+    let history = history_path();
+    let _ = editor.load_history(&history);
+
+    loop {
+        match editor.readline("rcalc> ") {
+            Ok(line) => {
+                let trimmed = line.trim();
+                if trimmed.is_empty() {
+                    continue;
+                }
+                let _ = editor.add_history_entry(trimmed);
+                let tokens: Vec<&str> = trimmed.split_whitespace().collect();
+                if let Err((position, err)) = process_tokens(&mut calculator, &tokens) {
+                    report_error(&tokens, position, &err);
+                }
+                stack_depth.set(calculator.stack.len());
+                print_stack(&calculator);
+            }
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                break;
+            }
+        }
+    }
+
+    editor.save_history(&history)?;
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() == 1 || args.iter().any(|a| a == "--repl") {
+        if let Err(err) = run_repl() {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut calculator = Calculator::new(default_conversions());
+
+    let tokens: Vec<&str> = args
+        .iter()
+        .skip(1)
+        .filter(|a| a.as_str() != "--repl")
+        .map(String::as_str)
+        .collect();
+
+    if let Err((position, err)) = process_tokens(&mut calculator, &tokens) {
+        report_error(&tokens, position, &err);
+        std::process::exit(1);
+    }
+
+    print_stack(&calculator);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combine_adds_exponents_with_sign() {
+        let per_second = Dimension::base(2, -1);
+        let length = Dimension::base(LENGTH, 1);
+        let speed = length.combine(per_second, 1);
+        assert_eq!(speed.0[LENGTH], 1);
+        assert_eq!(speed.0[2], -1);
+
+        let length_over_time_squared = speed.combine(per_second, 1);
+        assert_eq!(length_over_time_squared.0[2], -2);
+    }
+
+    #[test]
+    fn scale_multiplies_every_exponent() {
+        let area = Dimension::base(LENGTH, 1).scale(2);
+        assert_eq!(area.0[LENGTH], 2);
+        assert!(!area.is_dimensionless());
+
+        let dimensionless = Dimension::DIMENSIONLESS.scale(5);
+        assert!(dimensionless.is_dimensionless());
+    }
+
+    #[test]
+    fn halve_requires_every_exponent_even() {
+        let area = Dimension::base(LENGTH, 2);
+        let length = area.halve().expect("even exponents should halve");
+        assert_eq!(length.0[LENGTH], 1);
+
+        let volume = Dimension::base(LENGTH, 3);
+        assert!(volume.halve().is_none());
+    }
+
+    #[test]
+    fn pow_scales_dimension_by_integer_exponent() {
+        let mut calculator = Calculator::new(default_conversions());
+        calculator.push(2.0, Dimension::base(LENGTH, 1), 1.0);
+        calculator.push(3.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.pow().expect("integer exponent on a dimensioned base");
+        let result = calculator.pop().unwrap();
+        assert_eq!(result.value, 8.0);
+        assert_eq!(result.dimension.0[LENGTH], 3);
+    }
+
+    #[test]
+    fn pow_rejects_fractional_exponent_on_dimensioned_base() {
+        let mut calculator = Calculator::new(default_conversions());
+        calculator.push(2.0, Dimension::base(LENGTH, 1), 1.0);
+        calculator.push(0.5, Dimension::DIMENSIONLESS, 1.0);
+        assert!(matches!(
+            calculator.pow(),
+            Err(CalcError::ParseFailure(_))
+        ));
+    }
+
+    #[test]
+    fn pow_rejects_dimensioned_exponent() {
+        let mut calculator = Calculator::new(default_conversions());
+        calculator.push(2.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.push(3.0, Dimension::base(LENGTH, 1), 1.0);
+        assert!(matches!(
+            calculator.pow(),
+            Err(CalcError::DimensionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn sqrt_halves_dimension_and_rejects_odd_exponents() {
+        let mut calculator = Calculator::new(default_conversions());
+        calculator.push(9.0, Dimension::base(LENGTH, 2), 1.0);
+        calculator.sqrt().expect("even exponents should take a sqrt");
+        let result = calculator.pop().unwrap();
+        assert_eq!(result.value, 3.0);
+        assert_eq!(result.dimension.0[LENGTH], 1);
+
+        calculator.push(8.0, Dimension::base(LENGTH, 3), 1.0);
+        assert!(matches!(
+            calculator.sqrt(),
+            Err(CalcError::ParseFailure(_))
+        ));
+    }
+
+    fn push_lengths(calculator: &mut Calculator, values: &[f64]) {
+        let length = Dimension::base(LENGTH, 1);
+        for &value in values {
+            calculator.push(value, length, 1.0);
+        }
+    }
+
+    #[test]
+    fn sum_adds_every_element_and_collapses_the_stack() {
+        let mut calculator = Calculator::new(default_conversions());
+        push_lengths(&mut calculator, &[1.0, 2.0, 3.0]);
+        calculator.sum().unwrap();
+        assert_eq!(calculator.stack.len(), 1);
+        assert_eq!(calculator.pop().unwrap().value, 6.0);
+    }
+
+    #[test]
+    fn mean_divides_the_sum_by_the_original_count() {
+        let mut calculator = Calculator::new(default_conversions());
+        push_lengths(&mut calculator, &[1.0, 2.0, 3.0]);
+        calculator.mean().unwrap();
+        assert_eq!(calculator.pop().unwrap().value, 2.0);
+    }
+
+    #[test]
+    fn min_and_max_find_the_extremes() {
+        let mut calculator = Calculator::new(default_conversions());
+        push_lengths(&mut calculator, &[3.0, 1.0, 2.0]);
+        calculator.min().unwrap();
+        assert_eq!(calculator.pop().unwrap().value, 1.0);
+
+        let mut calculator = Calculator::new(default_conversions());
+        push_lengths(&mut calculator, &[3.0, 1.0, 2.0]);
+        calculator.max().unwrap();
+        assert_eq!(calculator.pop().unwrap().value, 3.0);
+    }
+
+    #[test]
+    fn product_multiplies_values_and_combines_dimensions() {
+        let mut calculator = Calculator::new(default_conversions());
+        calculator.push(2.0, Dimension::base(LENGTH, 1), 1.0);
+        calculator.push(3.0, Dimension::base(LENGTH, 1), 1.0);
+        calculator.product().unwrap();
+        let result = calculator.pop().unwrap();
+        assert_eq!(result.value, 6.0);
+        assert_eq!(result.dimension.0[LENGTH], 2);
+    }
+
+    #[test]
+    fn count_reports_stack_size_and_clears_it() {
+        let mut calculator = Calculator::new(default_conversions());
+        push_lengths(&mut calculator, &[1.0, 2.0, 3.0, 4.0]);
+        calculator.count().unwrap();
+        assert_eq!(calculator.pop().unwrap().value, 4.0);
+        assert!(calculator.stack.is_empty());
+    }
+
+    #[test]
+    fn aggregators_reject_mismatched_dimensions() {
+        let mut calculator = Calculator::new(default_conversions());
+        calculator.push(1.0, Dimension::base(LENGTH, 1), 1.0);
+        calculator.push(1.0, Dimension::DIMENSIONLESS, 1.0);
+        assert!(matches!(
+            calculator.sum(),
+            Err(CalcError::DimensionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_number_handles_alternate_radixes() {
+        assert_eq!(parse_number("0x1F"), Some(31.0));
+        assert_eq!(parse_number("0b101"), Some(5.0));
+        assert_eq!(parse_number("0o17"), Some(15.0));
+        assert_eq!(parse_number("0X1f"), Some(31.0));
+    }
+
+    #[test]
+    fn parse_number_strips_underscore_separators() {
+        assert_eq!(parse_number("1_000_000"), Some(1_000_000.0));
+        assert_eq!(parse_number("0x1_F"), Some(31.0));
+    }
+
+    #[test]
+    fn parse_number_handles_decimal_and_scientific_notation() {
+        assert_eq!(parse_number("3.5"), Some(3.5));
+        assert_eq!(parse_number("2.5e3"), Some(2500.0));
+        assert_eq!(parse_number("not a number"), None);
+    }
+
+    #[test]
+    fn split_unit_suffix_peels_trailing_letters() {
+        assert_eq!(split_unit_suffix("10cm"), ("10", "cm"));
+        assert_eq!(split_unit_suffix("3.5mi"), ("3.5", "mi"));
+        assert_eq!(split_unit_suffix("42"), ("42", ""));
+        assert_eq!(split_unit_suffix("ft"), ("", "ft"));
+    }
+
+    #[test]
+    fn tokens_satisfy_stack_allows_a_lone_trailing_operator_with_a_deep_enough_stack() {
+        assert!(tokens_satisfy_stack(&["+"], 2));
+        assert!(tokens_satisfy_stack(&["*"], 2));
+        assert!(!tokens_satisfy_stack(&["+"], 1));
+        assert!(!tokens_satisfy_stack(&["*"], 0));
+    }
+
+    #[test]
+    fn tokens_satisfy_stack_accounts_for_operands_typed_on_the_same_line() {
+        assert!(tokens_satisfy_stack(&["3", "4", "+"], 0));
+        assert!(!tokens_satisfy_stack(&["+", "3", "4"], 0));
+    }
+
+    #[test]
+    fn tokens_satisfy_stack_handles_aggregators_and_register_ops() {
+        assert!(tokens_satisfy_stack(&["sum"], 1));
+        assert!(!tokens_satisfy_stack(&["sum"], 0));
+        assert!(tokens_satisfy_stack(&["sto", "x"], 1));
+        assert!(!tokens_satisfy_stack(&["sto", "x"], 0));
+        assert!(tokens_satisfy_stack(&["rcl", "x"], 0));
+    }
+
+    #[test]
+    fn sto_and_rcl_round_trip_a_dimensioned_unit() {
+        let mut calculator = Calculator::new(default_conversions());
+        calculator.push(5.0, Dimension::base(LENGTH, 1), 1.0);
+        calculator.store("x").unwrap();
+        assert!(calculator.stack.is_empty());
+
+        calculator.recall("x").unwrap();
+        let recalled = calculator.pop().unwrap();
+        assert_eq!(recalled.value, 5.0);
+        assert_eq!(recalled.dimension.0[LENGTH], 1);
+
+        assert!(matches!(
+            calculator.recall("unset"),
+            Err(CalcError::ParseFailure(_))
+        ));
+    }
+
+    #[test]
+    fn dup_duplicates_the_top_without_disturbing_the_rest() {
+        let mut calculator = Calculator::new(default_conversions());
+        calculator.push(1.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.push(2.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.dup().unwrap();
+        assert_eq!(calculator.stack.len(), 3);
+        assert_eq!(calculator.pop().unwrap().value, 2.0);
+        assert_eq!(calculator.pop().unwrap().value, 2.0);
+        assert_eq!(calculator.pop().unwrap().value, 1.0);
+    }
+
+    #[test]
+    fn drop_top_removes_the_top_entry_and_underflows_when_empty() {
+        let mut calculator = Calculator::new(default_conversions());
+        assert!(matches!(
+            calculator.drop_top(),
+            Err(CalcError::StackUnderflow)
+        ));
+
+        calculator.push(1.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.push(2.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.drop_top().unwrap();
+        assert_eq!(calculator.pop().unwrap().value, 1.0);
+    }
+
+    #[test]
+    fn swap_and_dup_underflow_on_a_too_short_stack() {
+        let mut calculator = Calculator::new(default_conversions());
+        assert!(matches!(calculator.dup(), Err(CalcError::StackUnderflow)));
+        assert!(matches!(calculator.swap(), Err(CalcError::StackUnderflow)));
+
+        calculator.push(1.0, Dimension::DIMENSIONLESS, 1.0);
+        assert!(matches!(calculator.swap(), Err(CalcError::StackUnderflow)));
+    }
+
+    #[test]
+    fn swap_exchanges_the_top_two_entries() {
+        let mut calculator = Calculator::new(default_conversions());
+        calculator.push(1.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.push(2.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.swap().unwrap();
+        assert_eq!(calculator.pop().unwrap().value, 1.0);
+        assert_eq!(calculator.pop().unwrap().value, 2.0);
+    }
+
+    #[test]
+    fn clear_empties_the_stack() {
+        let mut calculator = Calculator::new(default_conversions());
+        calculator.push(1.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.push(2.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.clear().unwrap();
+        assert!(calculator.stack.is_empty());
+    }
+
+    #[test]
+    fn assert_stack_height_pops_only_when_satisfied() {
+        let mut calculator = Calculator::new(default_conversions());
+        calculator.push(1.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.push(2.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.push(1.0, Dimension::DIMENSIONLESS, 1.0);
+
+        assert!(matches!(
+            calculator.assert_stack_height(),
+            Err(CalcError::ParseFailure(_))
+        ));
+        assert_eq!(calculator.stack.len(), 3);
+
+        calculator.pop();
+        calculator.push(2.0, Dimension::DIMENSIONLESS, 1.0);
+        calculator.assert_stack_height().unwrap();
+        assert_eq!(calculator.stack.len(), 2);
     }
 }